@@ -2,18 +2,24 @@
 
 use std::{
     cmp,
+    collections::{HashMap, VecDeque},
+    future::Future,
+    pin::Pin,
     sync::{
         atomic::{AtomicPtr, AtomicU64, AtomicUsize, Ordering},
         Arc,
     },
+    task::{Context, Poll, Waker},
+    time::{Duration, Instant},
 };
 
-use crossbeam::channel::{RecvError, SendError, TryRecvError, TrySendError};
+use crossbeam::channel::{RecvError, RecvTimeoutError, SendError, TryRecvError, TrySendError};
 use crossbeam_skiplist::SkipMap;
+use futures::stream::Stream;
 use parking_lot::{Condvar, Mutex};
 
 pub fn unbounded<T: Send>() -> (Sender<T>, Receiver<T>) {
-    let queue = Arc::new(PriorityQueue::new());
+    let queue = Arc::new(PriorityQueue::new(usize::MAX));
     let sender = Sender {
         inner: queue.clone(),
     };
@@ -21,6 +27,53 @@ pub fn unbounded<T: Send>() -> (Sender<T>, Receiver<T>) {
     (sender, receiver)
 }
 
+/// Creates a priority channel whose queue holds at most `cap` messages.
+///
+/// Once the queue is full, [`Sender::send`] blocks until the receiver pops an
+/// entry, and [`Sender::try_send`] returns [`TrySendError::Full`] instead of
+/// blocking.
+pub fn bounded<T: Send>(cap: usize) -> (Sender<T>, Receiver<T>) {
+    let queue = Arc::new(PriorityQueue::new(cap));
+    let sender = Sender {
+        inner: queue.clone(),
+    };
+    let receiver = Receiver { inner: queue };
+    (sender, receiver)
+}
+
+/// Creates an unbounded priority channel that schedules messages by weighted
+/// fair queuing instead of strict priority order.
+///
+/// Every priority class still gets preference proportional to its weight
+/// (see [`class_weight`]), but a steady stream of high-priority messages can
+/// no longer starve lower classes completely, unlike [`unbounded`].
+pub fn weighted_fair<T: Send>() -> (Sender<T>, Receiver<T>) {
+    let queue = Arc::new(PriorityQueue::with_mode(Mode::WeightedFair, usize::MAX));
+    let sender = Sender {
+        inner: queue.clone(),
+    };
+    let receiver = Receiver { inner: queue };
+    (sender, receiver)
+}
+
+/// The weight given to a priority class under [`weighted_fair`] scheduling.
+///
+/// Mirrors `kvproto`'s `CommandPri` representation (`Normal` = 0, `Low` = 1,
+/// `High` = 2): high-priority messages get four times the throughput share
+/// of low-priority ones, with normal in between.
+fn class_weight(pri: u64) -> u64 {
+    match pri {
+        2 => 4,
+        1 => 1,
+        _ => 2,
+    }
+}
+
+/// Fixed-point scale applied to `msg_cost` before dividing by a class's
+/// weight, so that small integer costs still produce a meaningful spread of
+/// virtual finish times.
+const COST_SCALE: u64 = 1 << 20;
+
 struct Cell<T> {
     ptr: AtomicPtr<T>,
 }
@@ -57,7 +110,35 @@ struct PriorityQueue<T> {
     disconnected: Mutex<bool>,
     available: Condvar,
 
-    // cap: AtomicUsize,
+    cap: AtomicUsize,
+    // Number of entries currently in `queue`. `SkipMap::len()` is an O(n)
+    // traversal (the crate keeps no live counter), so capacity checks gate on
+    // this instead; kept in sync with `queue` under `full` (see below) so
+    // enqueuers never observe it out of step with a concurrent enqueue.
+    len: AtomicUsize,
+    // Paired with `not_full` so senders can block until the receiver frees up
+    // space; unused when `cap` is `usize::MAX` (i.e. unbounded). Also
+    // serializes the capacity check against the insert on every enqueue path,
+    // so concurrent senders can't all observe spare capacity and collectively
+    // overshoot `cap`.
+    full: Mutex<()>,
+    not_full: Condvar,
+
+    // Wakers for async tasks parked on `Receiver::recv_async`/`poll_next`,
+    // and for tasks parked on `Sender::send_async` waiting on capacity.
+    // `VecDeque` so waking goes FIFO (oldest waiter first); a `Vec` popped
+    // LIFO would let newly-registered waiters keep cutting in line ahead of
+    // ones that have already been waiting, starving them indefinitely under
+    // sustained contention.
+    recv_wakers: Mutex<VecDeque<Waker>>,
+    send_wakers: Mutex<VecDeque<Waker>>,
+
+    mode: Mode,
+    // Only used in `Mode::WeightedFair`: the virtual clock advanced on every
+    // pop, and the last virtual finish time handed out per priority class.
+    virtual_time: AtomicU64,
+    last_finish: Mutex<HashMap<u64, u64>>,
+
     sequencer: AtomicU64,
 
     senders: AtomicUsize,
@@ -65,31 +146,137 @@ struct PriorityQueue<T> {
 }
 
 impl<T> PriorityQueue<T> {
-    pub fn new() -> Self {
+    pub fn new(cap: usize) -> Self {
+        Self::with_mode(Mode::Strict, cap)
+    }
+
+    pub fn with_mode(mode: Mode, cap: usize) -> Self {
         Self {
             queue: SkipMap::new(),
             disconnected: Mutex::new(false),
             available: Condvar::new(),
+            cap: AtomicUsize::new(cap),
+            len: AtomicUsize::new(0),
+            full: Mutex::new(()),
+            not_full: Condvar::new(),
+            recv_wakers: Mutex::new(VecDeque::new()),
+            send_wakers: Mutex::new(VecDeque::new()),
+            mode,
+            virtual_time: AtomicU64::new(0),
+            last_finish: Mutex::new(HashMap::new()),
             sequencer: AtomicU64::new(0),
             senders: AtomicUsize::new(1),
             receivers: AtomicUsize::new(1),
         }
     }
 
-    pub fn get_map_key(&self, pri: u64) -> MapKey {
-        MapKey {
-            priority: pri,
-            sequence: self.sequencer.fetch_add(1, Ordering::Relaxed),
+    fn is_bounded(&self) -> bool {
+        self.cap.load(Ordering::Relaxed) != usize::MAX
+    }
+
+    fn register_recv_waker(&self, waker: Waker) {
+        self.recv_wakers.lock().push_back(waker);
+    }
+
+    fn wake_one_receiver(&self) {
+        if let Some(waker) = self.recv_wakers.lock().pop_front() {
+            waker.wake();
         }
     }
+
+    fn register_send_waker(&self, waker: Waker) {
+        self.send_wakers.lock().push_back(waker);
+    }
+
+    fn wake_one_sender(&self) {
+        if let Some(waker) = self.send_wakers.lock().pop_front() {
+            waker.wake();
+        }
+    }
+
+    /// Accounts for `n` entries having been popped off `queue` and notifies
+    /// waiting senders, touching `not_full`/the send wakers once for the
+    /// whole batch rather than once per popped entry.
+    fn release_capacity(&self, n: usize) {
+        if n == 0 {
+            return;
+        }
+        self.len.fetch_sub(n, Ordering::AcqRel);
+        if !self.is_bounded() {
+            return;
+        }
+        // Taking `full` here closes the same race that `send_with_cost`'s
+        // capacity check guards against: a sender checking `len` under the
+        // same lock either observes the freed slots directly, or is already
+        // parked on `not_full` (registering as a waiter and releasing the
+        // lock happen atomically) by the time we acquire the lock here.
+        let _full = self.full.lock();
+        self.not_full.notify_all();
+        for _ in 0..n {
+            self.wake_one_sender();
+        }
+    }
+
+    pub fn get_map_key(&self, pri: u64, cost: u64) -> MapKey {
+        let sequence = self.sequencer.fetch_add(1, Ordering::Relaxed);
+        if self.mode == Mode::WeightedFair {
+            let weight = class_weight(pri);
+            let mut last_finish = self.last_finish.lock();
+            let virtual_time = self.virtual_time.load(Ordering::Relaxed);
+            let start = (*last_finish.get(&pri).unwrap_or(&0)).max(virtual_time);
+            let finish = start.saturating_add(cost.saturating_mul(COST_SCALE) / weight);
+            last_finish.insert(pri, finish);
+            MapKey {
+                priority: finish,
+                sequence,
+                start,
+            }
+        } else {
+            MapKey {
+                priority: pri,
+                sequence,
+                start: 0,
+            }
+        }
+    }
+}
+
+/// Selects how a [`PriorityQueue`] orders messages for dequeue.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    /// Always dequeue the highest-priority message first; a steady stream of
+    /// high-priority sends can starve lower classes indefinitely.
+    Strict,
+    /// Dequeue in order of virtual finish time, so every class makes
+    /// progress proportional to its [`class_weight`].
+    WeightedFair,
+}
+
+impl Default for Mode {
+    fn default() -> Self {
+        Mode::Strict
+    }
 }
 
-#[derive(Eq, PartialEq)]
 struct MapKey {
     priority: u64,
     sequence: u64,
+    // The virtual *start* tag assigned to the entry on enqueue, for
+    // `Mode::WeightedFair`; unused (always `0`) otherwise. Kept out of
+    // `Ord`/`Eq` below since it doesn't participate in dequeue order;
+    // `try_recv` just reads it back off the popped entry to advance
+    // `virtual_time`.
+    start: u64,
+}
+
+impl PartialEq for MapKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == cmp::Ordering::Equal
+    }
 }
 
+impl Eq for MapKey {}
+
 impl PartialOrd for MapKey {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
         Some(self.cmp(other))
@@ -113,24 +300,127 @@ pub struct Sender<T: Send> {
 
 impl<T: Send + 'static> Sender<T> {
     pub fn try_send(&self, msg: T, pri: u64) -> Result<(), TrySendError<T>> {
-        self.send(msg, pri)
-            .map_err(|SendError(msg)| TrySendError::Disconnected(msg))
+        self.try_send_with_cost(msg, pri, 1)
+    }
+
+    /// Like [`try_send`](Self::try_send), but lets the caller supply the
+    /// message's cost for [`weighted_fair`] scheduling; ignored in other
+    /// modes. Defaults to `1` via [`try_send`](Self::try_send).
+    pub fn try_send_with_cost(&self, msg: T, pri: u64, cost: u64) -> Result<(), TrySendError<T>> {
+        if self.inner.receivers.load(Ordering::Acquire) == 0 {
+            return Err(TrySendError::Disconnected(msg));
+        }
+        // Held across both the capacity check and the insert below so
+        // concurrent callers can't all observe spare capacity and
+        // collectively overshoot `cap`.
+        let _full = self.inner.is_bounded().then(|| self.inner.full.lock());
+        if self.inner.is_bounded()
+            && self.inner.len.load(Ordering::Acquire) >= self.inner.cap.load(Ordering::Relaxed)
+        {
+            return Err(TrySendError::Full(msg));
+        }
+        self.inner
+            .queue
+            .insert(self.inner.get_map_key(pri, cost), Cell::new(msg));
+        self.inner.len.fetch_add(1, Ordering::AcqRel);
+        self.inner.available.notify_one();
+        self.inner.wake_one_receiver();
+        Ok(())
+    }
+
+    /// Like [`send`](Self::send), but yields to the async executor instead of
+    /// blocking the thread while the queue is at capacity.
+    pub async fn send_async(&self, msg: T, pri: u64) -> Result<(), SendError<T>> {
+        SendFuture {
+            sender: self,
+            pri,
+            msg: Some(msg),
+        }
+        .await
     }
 
     pub fn send(&self, msg: T, pri: u64) -> Result<(), SendError<T>> {
+        self.send_with_cost(msg, pri, 1)
+    }
+
+    /// Like [`send`](Self::send), but lets the caller supply the message's
+    /// cost for [`weighted_fair`] scheduling; ignored in other modes.
+    pub fn send_with_cost(&self, msg: T, pri: u64, cost: u64) -> Result<(), SendError<T>> {
+        // Held across the capacity wait *and* the insert below (not just the
+        // wait), so a concurrent `try_send_with_cost`/`send_with_cost` can't
+        // slip an insert in between this call observing spare capacity and
+        // actually claiming it.
+        let mut full_guard = if self.inner.is_bounded() {
+            Some(self.inner.full.lock())
+        } else {
+            None
+        };
+        if let Some(full) = full_guard.as_mut() {
+            let cap = self.inner.cap.load(Ordering::Relaxed);
+            while self.inner.len.load(Ordering::Acquire) >= cap {
+                if self.inner.receivers.load(Ordering::Acquire) == 0 {
+                    return Err(SendError(msg));
+                }
+                self.inner.not_full.wait(full);
+            }
+        }
         if self.inner.receivers.load(Ordering::Acquire) == 0 {
             return Err(SendError(msg));
         }
         self.inner
             .queue
-            .insert(self.inner.get_map_key(pri), Cell::new(msg));
+            .insert(self.inner.get_map_key(pri, cost), Cell::new(msg));
+        self.inner.len.fetch_add(1, Ordering::AcqRel);
         self.inner.available.notify_one();
+        self.inner.wake_one_receiver();
         Ok(())
     }
 
     #[cfg(test)]
     fn len(&self) -> usize {
-        self.inner.queue.len()
+        self.inner.len.load(Ordering::Acquire)
+    }
+}
+
+/// Future backing [`Sender::send_async`].
+///
+/// Polling retries [`Sender::try_send`] and, if the queue is still full,
+/// registers the task's waker to be woken once the receiver makes room.
+struct SendFuture<'a, T: Send> {
+    sender: &'a Sender<T>,
+    pri: u64,
+    msg: Option<T>,
+}
+
+// `SendFuture` holds no self-referential state (`msg` is moved in and out
+// wholesale on each poll), so it's sound to unpin regardless of `T`; this
+// lets `poll` use `get_mut()` instead of threading `unsafe` pin projection
+// through a type that has no need for pinning.
+impl<'a, T: Send> Unpin for SendFuture<'a, T> {}
+
+impl<'a, T: Send + 'static> Future for SendFuture<'a, T> {
+    type Output = Result<(), SendError<T>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let msg = this.msg.take().expect("SendFuture polled after completion");
+        match this.sender.try_send(msg, this.pri) {
+            Ok(()) => Poll::Ready(Ok(())),
+            Err(TrySendError::Disconnected(msg)) => Poll::Ready(Err(SendError(msg))),
+            Err(TrySendError::Full(msg)) => {
+                this.sender.inner.register_send_waker(cx.waker().clone());
+                // Re-check in case a slot freed up right after the first
+                // `try_send` but before the waker was registered.
+                match this.sender.try_send(msg, this.pri) {
+                    Ok(()) => Poll::Ready(Ok(())),
+                    Err(TrySendError::Disconnected(msg)) => Poll::Ready(Err(SendError(msg))),
+                    Err(TrySendError::Full(msg)) => {
+                        this.msg = Some(msg);
+                        Poll::Pending
+                    }
+                }
+            }
+        }
     }
 }
 
@@ -149,6 +439,9 @@ impl<T: Send> Drop for Sender<T> {
         if old <= 1 {
             *self.inner.disconnected.lock() = true;
             self.inner.available.notify_all();
+            for waker in self.inner.recv_wakers.lock().drain(..) {
+                waker.wake();
+            }
         }
     }
 }
@@ -160,7 +453,15 @@ pub struct Receiver<T: Send> {
 impl<T: Send + 'static> Receiver<T> {
     pub fn try_recv(&self) -> Result<T, TryRecvError> {
         match self.inner.queue.pop_front() {
-            Some(entry) => Ok(entry.value().take().unwrap()),
+            Some(entry) => {
+                self.inner.release_capacity(1);
+                if self.inner.mode == Mode::WeightedFair {
+                    self.inner
+                        .virtual_time
+                        .fetch_max(entry.key().start, Ordering::Relaxed);
+                }
+                Ok(entry.value().take().unwrap())
+            }
             None if self.inner.senders.load(Ordering::SeqCst) == 0 => {
                 Err(TryRecvError::Disconnected)
             }
@@ -186,9 +487,118 @@ impl<T: Send + 'static> Receiver<T> {
         }
     }
 
+    /// Like [`recv`](Self::recv), but returns [`RecvTimeoutError::Timeout`]
+    /// if no message arrives before `timeout` elapses.
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        self.recv_deadline(Instant::now() + timeout)
+    }
+
+    /// Like [`recv`](Self::recv), but returns [`RecvTimeoutError::Timeout`]
+    /// if no message arrives before `deadline`.
+    pub fn recv_deadline(&self, deadline: Instant) -> Result<T, RecvTimeoutError> {
+        loop {
+            match self.try_recv() {
+                Ok(msg) => return Ok(msg),
+                Err(TryRecvError::Disconnected) => return Err(RecvTimeoutError::Disconnected),
+                Err(TryRecvError::Empty) => {
+                    let mut disconnected = self.inner.disconnected.lock();
+                    if *disconnected {
+                        return Err(RecvTimeoutError::Disconnected);
+                    }
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return Err(RecvTimeoutError::Timeout);
+                    }
+                    // A spurious wakeup just loops back around to `try_recv`
+                    // with the remaining time recomputed.
+                    self.inner
+                        .available
+                        .wait_for(&mut disconnected, deadline - now);
+                }
+            }
+        }
+    }
+
+    /// Like [`recv`](Self::recv), but yields to the async executor instead
+    /// of blocking the thread while the queue is empty.
+    pub async fn recv_async(&self) -> Result<T, RecvError> {
+        futures::future::poll_fn(|cx| self.poll_recv(cx))
+            .await
+            .ok_or(RecvError)
+    }
+
+    fn poll_recv(&self, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        match self.try_recv() {
+            Ok(msg) => Poll::Ready(Some(msg)),
+            Err(TryRecvError::Disconnected) => Poll::Ready(None),
+            Err(TryRecvError::Empty) => {
+                self.inner.register_recv_waker(cx.waker().clone());
+                // Re-check in case a message arrived right after the first
+                // `try_recv` but before the waker was registered.
+                match self.try_recv() {
+                    Ok(msg) => Poll::Ready(Some(msg)),
+                    Err(TryRecvError::Disconnected) => Poll::Ready(None),
+                    Err(TryRecvError::Empty) => Poll::Pending,
+                }
+            }
+        }
+    }
+
+    /// Pops up to `max` messages in priority order into `out`, returning how
+    /// many were received. Does not block; stops early once the queue is
+    /// empty.
+    pub fn recv_batch(&self, max: usize, out: &mut Vec<T>) -> usize {
+        let mut n = 0;
+        while n < max {
+            match self.inner.queue.pop_front() {
+                Some(entry) => {
+                    if self.inner.mode == Mode::WeightedFair {
+                        self.inner
+                            .virtual_time
+                            .fetch_max(entry.key().start, Ordering::Relaxed);
+                    }
+                    out.push(entry.value().take().unwrap());
+                    n += 1;
+                }
+                None => break,
+            }
+        }
+        // A single capacity notification for the whole batch, rather than
+        // one per popped entry as a loop of `try_recv` calls would do.
+        self.inner.release_capacity(n);
+        n
+    }
+
+    /// Returns an iterator that pops queued messages in priority order
+    /// without blocking, stopping once the queue is empty.
+    pub fn try_iter(&self) -> TryIter<'_, T> {
+        TryIter { receiver: self }
+    }
+
     #[cfg(test)]
     fn len(&self) -> usize {
-        self.inner.queue.len()
+        self.inner.len.load(Ordering::Acquire)
+    }
+}
+
+/// Iterator returned by [`Receiver::try_iter`].
+pub struct TryIter<'a, T: Send> {
+    receiver: &'a Receiver<T>,
+}
+
+impl<'a, T: Send + 'static> Iterator for TryIter<'a, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.receiver.try_recv().ok()
+    }
+}
+
+impl<T: Send + 'static> Stream for Receiver<T> {
+    type Item = T;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        self.poll_recv(cx)
     }
 }
 
@@ -203,7 +613,22 @@ impl<T: Send> Clone for Receiver<T> {
 
 impl<T: Send> Drop for Receiver<T> {
     fn drop(&mut self) {
-        self.inner.receivers.fetch_sub(1, Ordering::AcqRel);
+        let old = self.inner.receivers.fetch_sub(1, Ordering::AcqRel);
+        if old <= 1 {
+            // Wake any sender blocked on capacity so it can observe that
+            // there are no receivers left and return an error instead of
+            // waiting forever. Taking `full` first closes the same race that
+            // `release_capacity` guards against: a sender re-checking
+            // `receivers` either sees zero directly or is already parked by
+            // the time we notify.
+            {
+                let _full = self.inner.full.lock();
+                self.inner.not_full.notify_all();
+            }
+            for waker in self.inner.send_wakers.lock().drain(..) {
+                waker.wake();
+            }
+        }
     }
 }
 
@@ -211,7 +636,7 @@ impl<T: Send> Drop for Receiver<T> {
 mod tests {
     use std::{sync::atomic::AtomicU64, thread, time::Duration};
 
-    use crossbeam::channel::TrySendError;
+    use crossbeam::channel::{RecvTimeoutError, TrySendError};
     use rand::Rng;
 
     use super::*;
@@ -261,6 +686,243 @@ mod tests {
         assert_eq!(rx.recv(), Err(RecvError));
     }
 
+    #[test]
+    fn test_bounded() {
+        let (tx, rx) = super::bounded::<u64>(2);
+        tx.send(1, CommandPri::Normal).unwrap();
+        tx.send(2, CommandPri::Normal).unwrap();
+        assert_eq!(
+            tx.try_send(3, CommandPri::Normal),
+            Err(TrySendError::Full(3))
+        );
+
+        let tx2 = tx.clone();
+        let handle = thread::spawn(move || {
+            tx2.send(3, CommandPri::Normal).unwrap();
+        });
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(rx.try_recv(), Ok(1));
+        handle.join().unwrap();
+        assert_eq!(rx.try_recv(), Ok(2));
+        assert_eq!(rx.try_recv(), Ok(3));
+
+        // A sender blocked on capacity must wake up once the last receiver is
+        // dropped, rather than waiting forever.
+        tx.send(4, CommandPri::Normal).unwrap();
+        tx.send(5, CommandPri::Normal).unwrap();
+        let tx3 = tx.clone();
+        let handle = thread::spawn(move || tx3.send(6, CommandPri::Normal));
+        thread::sleep(Duration::from_millis(50));
+        drop(rx);
+        assert_eq!(handle.join().unwrap(), Err(SendError(6)));
+    }
+
+    // Regression test for a lost-wakeup race: without synchronizing the
+    // `not_full` notification in `try_recv` against the capacity check in
+    // `send_with_cost`, a receiver that pops and notifies in the tiny window
+    // right before the sender parks on the condvar can leave the sender
+    // blocked forever even though the slot it is waiting for is free. A
+    // sleep-free, tight back-and-forth is much more likely to hit that
+    // window than tests that serialize sender/receiver with `thread::sleep`.
+    #[test]
+    fn test_bounded_no_lost_wakeup() {
+        let (tx, rx) = super::bounded::<u64>(1);
+        let sender = thread::spawn(move || {
+            for i in 0..10_000u64 {
+                tx.send(i, CommandPri::Normal).unwrap();
+            }
+        });
+        for _ in 0..10_000u64 {
+            rx.recv().unwrap();
+        }
+        sender.join().unwrap();
+    }
+
+    // Regression test: the capacity check and the insert must be atomic with
+    // respect to each other, or concurrent `try_send_with_cost` callers can
+    // all observe spare capacity and collectively overshoot `cap`.
+    #[test]
+    fn test_bounded_try_send_does_not_overshoot_capacity() {
+        const CAP: usize = 4;
+        let (tx, _rx) = super::bounded::<u64>(CAP);
+
+        let handles: Vec<_> = (0..16)
+            .map(|i| {
+                let tx = tx.clone();
+                thread::spawn(move || tx.try_send(i, CommandPri::Normal).is_ok())
+            })
+            .collect();
+        let accepted = handles
+            .into_iter()
+            .map(|h| h.join().unwrap())
+            .filter(|ok| *ok)
+            .count();
+
+        assert_eq!(accepted, CAP);
+        assert_eq!(tx.len(), CAP);
+    }
+
+    // Regression test: the waker registries must wake in FIFO order. A `Vec`
+    // popped LIFO would instead wake the most-recently-registered waiter
+    // first, letting new waiters cut in line ahead of ones that have been
+    // waiting longer.
+    #[test]
+    fn test_recv_wakers_fire_in_fifo_order() {
+        let queue: PriorityQueue<u64> = PriorityQueue::new(usize::MAX);
+        let order = Arc::new(Mutex::new(Vec::new()));
+
+        struct RecordingWaker {
+            id: u64,
+            order: Arc<Mutex<Vec<u64>>>,
+        }
+        impl std::task::Wake for RecordingWaker {
+            fn wake(self: Arc<Self>) {
+                self.order.lock().push(self.id);
+            }
+        }
+
+        for id in 0..3u64 {
+            let waker: Waker = Arc::new(RecordingWaker {
+                id,
+                order: order.clone(),
+            })
+            .into();
+            queue.register_recv_waker(waker);
+        }
+        queue.wake_one_receiver();
+        queue.wake_one_receiver();
+        queue.wake_one_receiver();
+
+        assert_eq!(*order.lock(), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn test_recv_timeout() {
+        let (tx, rx) = super::unbounded::<u64>();
+        assert_eq!(
+            rx.recv_timeout(Duration::from_millis(20)),
+            Err(RecvTimeoutError::Timeout)
+        );
+
+        tx.send(1, CommandPri::Normal).unwrap();
+        assert_eq!(rx.recv_timeout(Duration::from_millis(20)), Ok(1));
+
+        let tx2 = tx.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            tx2.send(2, CommandPri::Normal).unwrap();
+        });
+        assert_eq!(
+            rx.recv_deadline(Instant::now() + Duration::from_secs(1)),
+            Ok(2)
+        );
+
+        drop(tx);
+        assert_eq!(
+            rx.recv_timeout(Duration::from_millis(20)),
+            Err(RecvTimeoutError::Disconnected)
+        );
+    }
+
+    #[test]
+    fn test_async() {
+        use futures::{executor::block_on, StreamExt};
+
+        let (tx, mut rx) = super::unbounded::<u64>();
+        block_on(tx.send_async(1, CommandPri::Normal)).unwrap();
+        assert_eq!(block_on(rx.recv_async()), Ok(1));
+
+        block_on(tx.send_async(2, CommandPri::Low)).unwrap();
+        block_on(tx.send_async(3, CommandPri::High)).unwrap();
+        assert_eq!(block_on(rx.next()), Some(3));
+        assert_eq!(block_on(rx.next()), Some(2));
+
+        drop(tx);
+        assert_eq!(block_on(rx.next()), None);
+        assert_eq!(block_on(rx.recv_async()), Err(RecvError));
+
+        let (tx, rx) = super::bounded::<u64>(1);
+        tx.send(1, CommandPri::Normal).unwrap();
+        let tx2 = tx.clone();
+        let handle = thread::spawn(move || block_on(tx2.send_async(2, CommandPri::Normal)));
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(rx.try_recv(), Ok(1));
+        handle.join().unwrap().unwrap();
+        assert_eq!(rx.try_recv(), Ok(2));
+    }
+
+    #[test]
+    fn test_recv_batch_and_try_iter() {
+        let (tx, rx) = super::unbounded::<u64>();
+        let mut out = Vec::new();
+        assert_eq!(rx.recv_batch(4, &mut out), 0);
+        assert!(out.is_empty());
+
+        tx.send(1, CommandPri::Low).unwrap();
+        tx.send(2, CommandPri::Normal).unwrap();
+        tx.send(3, CommandPri::High).unwrap();
+        assert_eq!(rx.recv_batch(2, &mut out), 2);
+        assert_eq!(out, vec![3, 2]);
+        assert_eq!(rx.recv_batch(2, &mut out), 1);
+        assert_eq!(out, vec![3, 2, 1]);
+
+        tx.send(4, CommandPri::Low).unwrap();
+        tx.send(5, CommandPri::High).unwrap();
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), vec![5, 4]);
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), Vec::<u64>::new());
+    }
+
+    #[test]
+    fn test_weighted_fair() {
+        let (tx, rx) = super::weighted_fair::<u64>();
+
+        // A High-priority flood (weight 4) must not starve Low (weight 1):
+        // sending one High between every Low still lets Low make progress,
+        // i.e. a Low message is served before all 8 High ones have drained.
+        for i in 0..8 {
+            tx.send(i, CommandPri::High).unwrap();
+            tx.send(100 + i, CommandPri::Low).unwrap();
+        }
+        let mut received = Vec::new();
+        for _ in 0..8 {
+            received.push(rx.try_recv().unwrap());
+        }
+        let low_count = received.iter().filter(|v| **v >= 100).count();
+        assert!(low_count > 0, "weighted-fair mode starved the Low class");
+
+        // Strict-priority channels are unaffected.
+        let (tx, rx) = super::unbounded::<u64>();
+        tx.send(1, CommandPri::Low).unwrap();
+        tx.send(2, CommandPri::High).unwrap();
+        assert_eq!(rx.try_recv(), Ok(2));
+        assert_eq!(rx.try_recv(), Ok(1));
+    }
+
+    #[test]
+    fn test_weighted_fair_virtual_time_tracks_start_tag() {
+        let (tx, rx) = super::weighted_fair::<u64>();
+
+        tx.send_with_cost(1, CommandPri::Low, 10).unwrap();
+        rx.try_recv().unwrap();
+        // The popped entry's *start* tag was 0 (the Low class was idle), so
+        // the virtual clock must still read 0 afterwards. Advancing it to
+        // the entry's *finish* tag instead would make every later idle
+        // class resume as though the clock had already run past this
+        // message's entire service time, eroding how much it's allowed to
+        // catch up.
+        assert_eq!(rx.inner.virtual_time.load(Ordering::Relaxed), 0);
+
+        tx.send_with_cost(2, CommandPri::Low, 10).unwrap();
+        rx.try_recv().unwrap();
+        // The second message's start tag equals the first message's finish
+        // tag, since the Low class's `last_finish` carried over; the clock
+        // should land exactly there.
+        assert_eq!(
+            rx.inner.virtual_time.load(Ordering::Relaxed),
+            10 * COST_SCALE,
+        );
+    }
+
     #[test]
     fn test_priority_multi_thread() {
         let (tx, rx) = super::unbounded::<u64>();